@@ -9,10 +9,7 @@ fn main() {
         println!("Simple arithmetic calculator. Just type expression and hit Enter. Type \"quit\" to exit.");
         println!("Example: (2+2)*5 - (-3+2.1)/(25*3.1415) + 0.0001");
 
-        let mut parser = arithmetic_parser::Parser {
-                numbers: vec![],
-                operations: vec![]
-        };
+        let mut parser = arithmetic_parser::Parser::new();
 
         loop {
                 print!("\n>> ");
@@ -27,14 +24,26 @@ fn main() {
                         break;
                 }
 
-                let _ = match parser.calculate(&expression) {
+                match parser.calculate(&expression) {
                         Ok(v) => println!("{}", v),
-                        Err(ref err) => match err {
-                                ParseError::BadExpression => println!("Bad expression"),
-                                ParseError::InvalidOperation => println!("Invalid operation"),
-                                ParseError::OperationBalance => println!("Parse error"),
-                                ParseError::PopFailure => println!("Parse error"),
-                        },
+                        Err(ref err) => report_error(&expression, err),
                 };
         }
 }
+
+/// Print a diagnostic for a failed calculation. When the error carries a
+/// source position, echo the (whitespace-stripped) expression and draw a caret
+/// under the offending column so the problem is easy to spot.
+fn report_error(expression: &String, err: &ParseError) {
+        match err.position() {
+                Some(pos) if pos >= 0 => {
+                        let mut stripped = expression.clone();
+                        common::remove_whitespace(&mut stripped);
+                        // For "name = expr" the position counts from the start of "expr"
+                        let offset = stripped.chars().position(|c| c == '=').map_or(0, |idx| idx + 1);
+                        println!("{}", stripped);
+                        println!("{}^ {}", " ".repeat(offset + pos as usize), err.message());
+                }
+                _ => println!("{}", err.message()),
+        }
+}
@@ -74,6 +74,43 @@ pub mod common {
         OperationBalance,
         PopFailure,
         BadExpression,
+        /// An operator or character that does not belong where it was found.
+        UnexpectedToken(i32),
+        /// A ")" without a matching "(" (or the other way round).
+        MismatchedParen(i32),
+        /// Division by a zero divisor, spotted while evaluating the tree.
+        DivisionByZero(i32),
+        /// The expression ended while an operand was still expected.
+        UnexpectedEnd(i32),
+    }
+
+    impl ParseError {
+        /// Character offset (into the whitespace-stripped expression that was
+        /// parsed; for `name = expr` that is `expr`) the error refers to, for
+        /// the ones that carry a position.
+        pub fn position(&self) -> Option<i32> {
+            match self {
+                ParseError::UnexpectedToken(pos)
+                | ParseError::MismatchedParen(pos)
+                | ParseError::DivisionByZero(pos)
+                | ParseError::UnexpectedEnd(pos) => Some(*pos),
+                _ => None,
+            }
+        }
+
+        /// Human-readable description of the error.
+        pub fn message(&self) -> &'static str {
+            match self {
+                ParseError::InvalidOperation => "invalid operation",
+                ParseError::OperationBalance => "unbalanced expression",
+                ParseError::PopFailure => "internal stack failure",
+                ParseError::BadExpression => "bad expression",
+                ParseError::UnexpectedToken(_) => "unexpected token",
+                ParseError::MismatchedParen(_) => "mismatched parenthesis",
+                ParseError::DivisionByZero(_) => "division by zero",
+                ParseError::UnexpectedEnd(_) => "unexpected end of input",
+            }
+        }
     }
 
     /// Remove whitespaces from string
@@ -89,10 +126,41 @@ pub mod common {
         op
     }
 
+    /// Return a string containing an identifier (function or variable name).
+    /// An identifier is a run of alphabetic characters.
+    fn get_identifier(expression: &String, pos: &mut i32) -> String {
+        let mut ident: String = String::new();
+        while *pos < (expression.len() as i32) && expression.chars().nth(*pos as usize).unwrap().is_alphabetic() {
+            ident.push(expression.chars().nth(*pos as usize).unwrap());
+            *pos += 1;
+        }
+        ident
+    }
+
     /// Return a string containing a number.
-    /// Work with numbers, including fractional ones. Delimiter "."
+    /// A leading "0x"/"0b"/"0o" prefix switches to the matching radix and only
+    /// its digit class is consumed; otherwise we read base-10 digits and "."
+    /// fraction delimiters. The whole lexeme (prefix included) is returned
+    /// and later turned into an `f64` by [`number_value`], which rejects a
+    /// lexeme with more than one ".".
     fn get_number(expression: &String, pos: &mut i32) -> String {
         let mut num: String = String::new();
+
+        // Detect a radix prefix ("0x", "0b", "0o"). A bare "0" with no radix
+        // letter following it stays on the decimal path below.
+        if expression.chars().nth(*pos as usize).unwrap() == '0' {
+            if let Some(radix) = expression.chars().nth((*pos + 1) as usize).and_then(radix_of) {
+                num.push('0');
+                num.push(expression.chars().nth((*pos + 1) as usize).unwrap());
+                *pos += 2;
+                while *pos < (expression.len() as i32) && expression.chars().nth(*pos as usize).unwrap().is_digit(radix) {
+                    num.push(expression.chars().nth(*pos as usize).unwrap());
+                    *pos += 1;
+                }
+                return num;
+            }
+        }
+
         while *pos < (expression.len() as i32) && (expression.chars().nth(*pos as usize).unwrap().is_digit(10) || expression.chars().nth(*pos as usize).unwrap() == '.') {
             num.push(expression.chars().nth(*pos as usize).unwrap());
             *pos += 1;
@@ -100,6 +168,30 @@ pub mod common {
         num
     }
 
+    /// Map a radix prefix letter to its base (`x` -> 16, `o` -> 8, `b` -> 2).
+    fn radix_of(c: char) -> Option<u32> {
+        match c {
+            'x' => Some(16),
+            'o' => Some(8),
+            'b' => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Convert a numeric lexeme produced by [`get_number`] into an `f64`,
+    /// honouring the "0x"/"0b"/"0o" radix prefixes. A prefix with no following
+    /// digit (or any otherwise malformed literal) is a `BadExpression`.
+    pub fn number_value(lexeme: &str) -> Result<f64, ParseError> {
+        if lexeme.len() > 2 {
+            if let Some(radix) = lexeme[1..2].chars().next().and_then(radix_of) {
+                return i64::from_str_radix(&lexeme[2..], radix)
+                    .map(|v| v as f64)
+                    .map_err(|_| ParseError::BadExpression);
+            }
+        }
+        lexeme.parse().map_err(|_| ParseError::BadExpression)
+    }
+
     /// Return token at specified position
     pub fn get_token(expression: &String, pos: &mut i32) -> Token {
         let mut result = Token {
@@ -117,6 +209,11 @@ pub mod common {
             result.set(get_number(&expression, pos), TokenType::Number);
             result
         }
+        // An alphabetic run is a function (or variable) name
+        else if expression.chars().nth(*pos as usize).unwrap().is_alphabetic() {
+            result.set(get_identifier(&expression, pos), TokenType::Function);
+            result
+        }
         // In other cases we have an operation (or incorrect input)
         else {
             result.set(get_operation(&expression, pos), TokenType::Operation);
@@ -130,8 +227,14 @@ pub mod common {
             // The open parenthesis is a special case and is also considered an operation. It does not pop anyone off the stack of operations, but it also does not allow itself to be popped off the stack.
             // Only a closing parenthesis can pop it. Accordingly, when the token ")" is received during parsing, it will pop all operations up to the first opening parenthesis.
             Ok(-1)
-        } else if operation == "*" || operation == "/" {
-            // Lowest priority for multiplication and division
+        } else if operation.chars().next().map_or(false, |c| c.is_alphabetic()) {
+            // A function binds tighter than any binary operator, so "sin(x)+1" groups as "(sin(x))+1"
+            Ok(0)
+        } else if operation == "^" {
+            // Exponentiation binds tighter than multiplication (but looser than a function call)
+            Ok(0)
+        } else if operation == "*" || operation == "/" || operation == "%" {
+            // Multiplication, division and modulo bind looser than exponentiation and tighter than addition and subtraction
             Ok(1)
         } else if operation == "+" || operation == "-" {
             // Highest priority for addition and subtraction. The highest priority operation pops the lowest priority operation from the stack.
@@ -148,6 +251,7 @@ pub mod arithmetic_parser {
 
     use super::token::*;
     use super::common::*;
+    use std::collections::HashMap;
 
     trait Stack<T> {
         fn top(&mut self) -> Option<&T>;
@@ -163,15 +267,113 @@ pub mod arithmetic_parser {
         }
     }
 
+    /// Node of the abstract syntax tree produced by [`Parser::parse`].
+    /// Keeping the parsed expression as a tree (rather than folding it into a
+    /// single `f64` on the fly) lets the same tree be evaluated, pretty-printed
+    /// or transformed later on.
+    pub enum Expr {
+        Number(f64),
+        BinaryOp { op: String, lhs: Box<Expr>, rhs: Box<Expr>, pos: i32 },
+        UnaryNeg(Box<Expr>),
+        Call { name: String, arg: Box<Expr> },
+        Var(String),
+    }
+
+    /// Walk an expression tree and compute its numeric value.
+    /// `vars` resolves [`Expr::Var`] references (named constants and the
+    /// variables assigned in earlier REPL lines).
+    pub fn eval(expr: &Expr, vars: &HashMap<String, f64>) -> Result<f64, ParseError> {
+        match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::Var(name) => vars.get(name).copied().ok_or(ParseError::BadExpression),
+            Expr::UnaryNeg(operand) => Ok(-eval(operand, vars)?),
+            Expr::BinaryOp { op, lhs, rhs, pos } => {
+                // Data was popped from the stack in reverse order during parsing,
+                // so the left operand is "b" and the right operand is "a"
+                let b = eval(lhs, vars)?;
+                let a = eval(rhs, vars)?;
+                match op.as_str() {
+                    "+" => Ok(b + a),
+                    "-" => Ok(b - a),
+                    "*" => Ok(b * a),
+                    "/" => {
+                        if a == 0.0 {
+                            Err(ParseError::DivisionByZero(*pos))
+                        } else {
+                            Ok(b / a)
+                        }
+                    }
+                    "%" => Ok(b % a),
+                    "^" => Ok(b.powf(a)),
+                    _ => Err(ParseError::InvalidOperation),
+                }
+            }
+            Expr::Call { name, arg } => {
+                let x = eval(arg, vars)?;
+                match name.as_str() {
+                    "sin" => Ok(x.sin()),
+                    "cos" => Ok(x.cos()),
+                    "sqrt" => Ok(x.sqrt()),
+                    "ln" => Ok(x.ln()),
+                    "abs" => Ok(x.abs()),
+                    "log" => Ok(x.log10()),
+                    _ => Err(ParseError::InvalidOperation),
+                }
+            }
+        }
+    }
+
     pub struct Parser {
-        pub numbers: std::vec::Vec<f64>,
-        pub operations: std::vec::Vec<String>,
+        pub exprs: std::vec::Vec<Expr>,
+        pub operations: std::vec::Vec<(String, i32)>,
+        pub variables: HashMap<String, f64>,
+    }
+
+    impl Default for Parser {
+        fn default() -> Parser {
+            Parser::new()
+        }
     }
 
     impl Parser {
 
-        /// Return a result of calculation of specified arithmetic expression
+        /// Create a parser with empty stacks and the variable map pre-seeded
+        /// with the usual named constants `pi` and `e`.
+        pub fn new() -> Parser {
+            let mut variables = HashMap::new();
+            variables.insert(String::from("pi"), std::f64::consts::PI);
+            variables.insert(String::from("e"), std::f64::consts::E);
+            Parser {
+                exprs: vec![],
+                operations: vec![],
+                variables,
+            }
+        }
+
+        /// Return a result of calculation of specified arithmetic expression.
+        /// A top-level `name = expr` stores the result under `name` (so it can
+        /// be referenced from later lines) and returns it.
         pub fn calculate(&mut self, origin_expression: &String) -> Result<f64, ParseError> {
+            if let Some(idx) = origin_expression.find('=') {
+                let name = origin_expression[..idx].trim().to_string();
+                if name.is_empty() || !name.chars().all(|c| c.is_alphabetic()) {
+                    return Err(ParseError::BadExpression);
+                }
+                let expr = self.parse(&origin_expression[idx + 1..])?;
+                let value = eval(&expr, &self.variables)?;
+                self.variables.insert(name, value);
+                return Ok(value);
+            }
+
+            let expr = self.parse(origin_expression)?;
+            eval(&expr, &self.variables)
+        }
+
+        /// Turn an arithmetic expression into an abstract syntax tree.
+        /// The shunting-yard algorithm is the same as before, but instead of
+        /// immediately computing a result it pops operand nodes off the
+        /// expression stack and pushes a `BinaryOp` node back.
+        pub fn parse(&mut self, origin_expression: &str) -> Result<Expr, ParseError> {
             let mut expression = format!("({})", origin_expression);
             remove_whitespace(&mut expression);
 
@@ -183,10 +385,15 @@ pub mod arithmetic_parser {
 
             let mut pos: i32 = 0;
 
-            self.numbers.clear();
+            self.exprs.clear();
             self.operations.clear();
 
             loop {
+                // Remember where this token starts so errors can point at it.
+                // The expression is wrapped in a synthetic "(", so the column in
+                // the user's input is one less than this offset.
+                let start = pos;
+
                 // Get token
                 token = get_token(&expression, &mut pos);
 
@@ -194,14 +401,27 @@ pub mod arithmetic_parser {
                 if token.is_operation() && ((token.get_value() == "+") || (token.get_value() == "-")) &&
                     prevtoken.is_operation() && (prevtoken.get_value() == "(") {
                     // Substitute 0. Thus, for example, the expression 4+(-1)*(2+2) becomes 4+(0-1)*(2 + 2)
-                    self.numbers.push(0.0);
+                    self.exprs.push(Expr::Number(0.0));
                 }
 
-                // If token is number then push it to stack of numbers
+                // If token is number then push it to stack of expressions
                 if token.is_number() {
-                    // convert the string to double
-                    let number: f64 = token.get_value().parse().unwrap();
-                    self.numbers.push(number);
+                    // convert the string (possibly a 0x/0b/0o literal) to double
+                    let number = number_value(&token.get_value())?;
+                    self.exprs.push(Expr::Number(number));
+                }
+
+                // An identifier immediately followed by "(" is a function call:
+                // it behaves like an operation, pushed onto the operation stack
+                // and applied to the single expression on top of the expression
+                // stack once its matching ")" pops it. Any other identifier is a
+                // variable reference resolved during evaluation.
+                if token.is_function() {
+                    if (pos as usize) < expression.len() && expression.chars().nth(pos as usize).unwrap() == '(' {
+                        self.operations.push((token.get_value(), start));
+                    } else {
+                        self.exprs.push(Expr::Var(token.get_value()));
+                    }
                 }
 
                 // If token is operation
@@ -210,7 +430,7 @@ pub mod arithmetic_parser {
                     // then checking for a closing parenthesis
                     if op == ")" {
                         // if it's a closing parenthesis, then pop up to the first opening parenthesis inclusive
-                        while !self.operations.is_empty() && self.operations.top().unwrap() != "(" {
+                        while !self.operations.is_empty() && self.operations.top().unwrap().0 != "(" {
                             let result = self.pop_operation();
                             let _ = match result {
                                 Ok(content) => { content },
@@ -218,9 +438,26 @@ pub mod arithmetic_parser {
                             };
                         }
 
+                        // A ")" with no matching "(" left on the stack is unbalanced
+                        if self.operations.is_empty() {
+                            return Err(ParseError::MismatchedParen(start - 1));
+                        }
+
                         // Open parenthesis is popped here
                         self.operations.pop();
+
+                        // A function name right below that "(" is the call this ")" closes: apply it now
+                        if self.operations.top().map_or(false, |top| top.0.chars().next().map_or(false, |c| c.is_alphabetic())) {
+                            if let Err(error) = self.pop_operation() {
+                                return Err(error);
+                            }
+                        }
                     } else {
+                        // An operator we do not recognise has no place here
+                        if op != "(" && get_priority(&op).is_err() {
+                            return Err(ParseError::UnexpectedToken(start - 1));
+                        }
+
                         // If we can pop the operation, then do it
                         if self.can_pop_operation(&op) {
                             let result = self.pop_operation();
@@ -231,7 +468,7 @@ pub mod arithmetic_parser {
                         }
 
                         // Push new operation to stack of operations
-                        self.operations.push(op);
+                        self.operations.push((op, start));
                     }
                 }
 
@@ -242,14 +479,19 @@ pub mod arithmetic_parser {
                 }
             }
 
-            if self.numbers.len() > 1 || self.operations.len() > 0 {
+            // An operation left on the stack means an "(" was never closed
+            if let Some((_, open_pos)) = self.operations.top() {
+                return Err(ParseError::MismatchedParen(*open_pos - 1));
+            }
+
+            if self.exprs.len() > 1 {
                 return Err(ParseError::BadExpression)
             }
 
-            // One number should remain at the top of the stack of numbers. This will be the result of calculations
-            match self.numbers.top() {
-                Some(&val) => Ok(val),
-                None => Err(ParseError::BadExpression),
+            // One node should remain at the top of the expression stack. This is the root of the tree
+            match self.exprs.pop() {
+                Some(expr) => Ok(expr),
+                None => Err(ParseError::UnexpectedEnd(0)),
             }
         }
 
@@ -264,7 +506,7 @@ pub mod arithmetic_parser {
                 // Priority of the operation at the top of the operation stack
                 match self.operations.top() {
                     Some(val) => {
-                        let prior2 = get_priority(val);
+                        let prior2 = get_priority(&val.0);
                         match prior1 {
                             Ok(v) => {
                                 let p1 = v;
@@ -274,7 +516,12 @@ pub mod arithmetic_parser {
                                         // We remember about the opening parenthesis (its priority is -1), it is non-popable (it will be popped out only by the closing parenthesis), so let's check the priorities for >= 0
                                         // If the priorities of the operations are equal, then we can pop. If the priority of the input operation is higher, then we can pop.
                                         // In other cases, we cannot pop
-                                        p1 >= 0 && p2 >= 0 && p1 >= p2
+                                        // A right-associative operator ("^") is the exception: it must not pop an equal-priority top, so "2^3^2" groups to the right. Hence the strict comparison.
+                                        if operation == "^" {
+                                            p1 >= 0 && p2 >= 0 && p1 > p2
+                                        } else {
+                                            p1 >= 0 && p2 >= 0 && p1 >= p2
+                                        }
                                     },
                                     Err(_) => false,
                                 }
@@ -287,15 +534,10 @@ pub mod arithmetic_parser {
             }
         }
 
-        fn pop_number(&mut self) -> Result<f64, ParseError> {
-            let x: f64;
-            if !self.numbers.is_empty() {
-                match self.numbers.top() {
-                    Some(&val) => {
-                        x = val;
-                        self.numbers.pop();
-                        Ok(x)
-                    },
+        fn pop_expr(&mut self) -> Result<Expr, ParseError> {
+            if !self.exprs.is_empty() {
+                match self.exprs.pop() {
+                    Some(expr) => Ok(expr),
                     None => Err(ParseError::PopFailure),
                 }
             } else {
@@ -305,36 +547,50 @@ pub mod arithmetic_parser {
 
         fn pop_operation(&mut self) -> Result<(), ParseError> {
 
-            // Pop the first number from the stack of numbers
-            let a = match self.pop_number() {
-                Ok(v) => v,
-                Err(_) => return Err(ParseError::OperationBalance),
+            // Look at the operation to pop together with the column it came from
+            let (operation, op_pos) = match self.operations.top() {
+                Some(val) => (val.0.clone(), val.1),
+                None => return Err(ParseError::PopFailure),
             };
 
-            // Pop the second number from the stack of numbers
-            let b = match self.pop_number() {
+            // A function takes a single operand, so handle it apart from the
+            // binary operators below: pop one expression and wrap it in a call.
+            if operation.chars().next().map_or(false, |c| c.is_alphabetic()) {
+                let arg = match self.pop_expr() {
+                    Ok(v) => v,
+                    Err(_) => return Err(ParseError::UnexpectedEnd(op_pos - 1)),
+                };
+                self.operations.pop();
+                self.exprs.push(Expr::Call {
+                    name: operation,
+                    arg: Box::new(arg),
+                });
+                return Ok(());
+            }
+
+            // Pop the first operand from the stack of expressions
+            let a = match self.pop_expr() {
                 Ok(v) => v,
-                Err(_) => return Err(ParseError::OperationBalance),
+                Err(_) => return Err(ParseError::UnexpectedEnd(op_pos - 1)),
             };
 
-            // Pop the operation
-            let operation = match self.operations.top() {
-                Some(val) => val.clone(),
-                None => return Err(ParseError::PopFailure),
+            // Pop the second operand from the stack of expressions
+            let b = match self.pop_expr() {
+                Ok(v) => v,
+                Err(_) => return Err(ParseError::UnexpectedEnd(op_pos - 1)),
             };
+
             self.operations.pop();
 
-            // Calculate and push the result to stack of numbers
-            // We take into account that data is popped from the stack in reverse order, so the first is "b", and then "a"
-            if operation == "+" {
-                self.numbers.push(b + a);
-            } else if operation == "-" {
-                self.numbers.push(b - a);
-            } else if operation == "*" {
-                self.numbers.push(b * a);
-            } else if operation == "/" {
-                self.numbers.push(b / a);
-            }
+            // Build a binary node rather than folding the operands right away.
+            // Data is popped from the stack in reverse order, so "b" is the left
+            // operand and "a" is the right operand
+            self.exprs.push(Expr::BinaryOp {
+                op: operation,
+                lhs: Box::new(b),
+                rhs: Box::new(a),
+                pos: op_pos - 1,
+            });
 
             Ok(())
         }
@@ -347,10 +603,7 @@ pub mod arithmetic_parser {
 
         #[test]
         fn test_sum() {
-            let mut parser = Parser {
-                numbers: vec![],
-                operations: vec![]
-            };
+            let mut parser = Parser::new();
 
             let exp = String::from("2+2");
             let expected: f64 = 4.0;